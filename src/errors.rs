@@ -18,20 +18,110 @@
 use std::error;
 use std::fmt::{Display, Formatter, Result};
 
+use matches::Matches;
+
+// Boxed slices keep the error small enough to be returned by value
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
     offender: String,
+    related: Box<[String]>,
+    value: Option<Box<str>>,
+    index: Option<usize>,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
     desc: String
 }
 
 impl Error {
     pub fn new(kind: ErrorKind, offender: String) -> Error {
-        Error {
-            kind: kind.clone(),
-            offender: offender.clone(),
-            desc: format!("{} {}", kind.description(), offender.clone()),
+        Error::with_related(kind, offender, Vec::new())
+    }
+
+    // Related names are any other opts which are relevant to the error, e.g. the candidates of an
+    // ambiguous abbreviation
+    pub fn with_related(kind: ErrorKind, offender: String, related: Vec<String>) -> Error {
+        let mut error = Error {
+            kind,
+            offender,
+            related: related.into_boxed_slice(),
+            value: None,
+            index: None,
+            source: None,
+            desc: String::new()
+        };
+        error.desc = error.describe();
+
+        error
+    }
+
+    // The value at fault, e.g. xml for --format=xml, since the offender is always the flag itself
+    pub fn with_value(mut self, value: String) -> Error {
+        self.value = Some(value.into_boxed_str());
+        self.desc = self.describe();
+        self
+    }
+
+    // The index of the environment argument at which the error occurred, e.g. 2 for -x in
+    // `prog -v -x`
+    pub fn at(mut self, index: usize) -> Error {
+        self.index = Some(index);
+        self
+    }
+
+    // The underlying error, e.g. a value's ParseIntError
+    pub fn caused_by(mut self, source: Box<dyn error::Error + Send + Sync>) -> Error {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn offender(&self) -> &str {
+        &self.offender
+    }
+
+    pub fn related(&self) -> &[String] {
+        &self.related
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    // Errors found after parsing, e.g. a missing required option, have no index
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    fn describe(&self) -> String {
+        let mut desc = format!("{} {}", self.kind.description(), self.offender);
+        if let Some(ref value) = self.value {
+            desc.push_str(&format!("={}", value));
+        }
+
+        if self.related.is_empty() {
+            return desc;
         }
+
+        match self.kind {
+            // Related names are suggestions for what was probably meant, e.g. --verbose for --verbsoe
+            ErrorKind::UnknownOption | ErrorKind::UnknownSubcommand => {
+                let names: Vec<String> = self.related.iter().map(|name| format!("`{}`", name)).collect();
+                desc.push_str(&format!(" (did you mean {}?)", names.join(" or ")));
+            },
+            _ => {
+                let related_desc = self.kind.related_description();
+                if related_desc.is_empty() {
+                    desc.push_str(&format!(" ({})", self.related.join(", ")));
+                } else {
+                    desc.push_str(&format!(" ({} {})", related_desc, self.related.join(", ")));
+                }
+            }
+        }
+
+        desc
     }
 }
 
@@ -39,28 +129,112 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         &self.desc
     }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.source {
+            Some(ref source) => Some(&**source),
+            None => None
+        }
+    }
 }
 
 impl Display for Error {
-    fn fmt(&self, f: &mut Formatter) -> Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "{}", self.desc)
     }
 }
 
-#[derive(Clone, Debug)]
+// Every error found while matching, along with whatever could still be matched
+#[derive(Debug)]
+pub struct Errors {
+    errors: Vec<Error>,
+    matches: Matches
+}
+
+impl Errors {
+    pub fn new(errors: Vec<Error>, matches: Matches) -> Errors {
+        Errors {
+            errors,
+            matches
+        }
+    }
+
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    pub fn matches(&self) -> &Matches {
+        &self.matches
+    }
+
+    pub fn into_matches(self) -> Matches {
+        self.matches
+    }
+}
+
+impl error::Error for Errors {}
+
+impl Display for Errors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self.errors.len() {
+            1 => write!(f, "1 error occurred:")?,
+            n => write!(f, "{} errors occurred:", n)?
+        }
+
+        for error in self.errors.iter() {
+            write!(f, "\n  {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
-    InvalidArgument,
-    MissingArgument,
-    TokenFormat
+    AmbiguousOption,
+    ConflictingOptions,
+    InvalidChoice,
+    InvalidValue,
+    MissingDependency,
+    MissingOption,
+    MissingPositional,
+    MissingValue,
+    NoMatch,
+    TokenFormat,
+    UnexpectedPositional,
+    UnexpectedValue,
+    UnknownOption,
+    UnknownSubcommand
 }
 
 impl ErrorKind {
     fn description(&self) -> String {
         match *self {
-            ErrorKind::InvalidArgument => String::from("An invalid option was passed to the program:"),
-            ErrorKind::MissingArgument => String::from("A required argument is missing:"),
-            ErrorKind::TokenFormat => String::from("A token was created in the wrong format:")
+            ErrorKind::AmbiguousOption => String::from("An ambiguous option was passed to the program:"),
+            ErrorKind::ConflictingOptions => String::from("Conflicting options were passed to the program:"),
+            ErrorKind::InvalidChoice => String::from("An invalid value was passed to the program:"),
+            ErrorKind::InvalidValue => String::from("A value could not be parsed:"),
+            ErrorKind::MissingDependency => String::from("An option was passed without the options it requires:"),
+            ErrorKind::MissingOption => String::from("A required option is missing:"),
+            ErrorKind::MissingPositional => String::from("A required argument is missing:"),
+            ErrorKind::MissingValue => String::from("An option is missing its value:"),
+            ErrorKind::NoMatch => String::from("No value was matched for:"),
+            ErrorKind::TokenFormat => String::from("A token was created in the wrong format:"),
+            ErrorKind::UnexpectedPositional => String::from("An unexpected argument was passed to the program:"),
+            ErrorKind::UnexpectedValue => String::from("A value was passed to a flag:"),
+            ErrorKind::UnknownOption => String::from("An invalid option was passed to the program:"),
+            ErrorKind::UnknownSubcommand => String::from("An invalid subcommand was passed to the program:")
         }
     }
-}
 
+    fn related_description(&self) -> String {
+        match *self {
+            ErrorKind::AmbiguousOption => String::from("could be:"),
+            ErrorKind::ConflictingOptions => String::from("conflicts with:"),
+            ErrorKind::InvalidChoice => String::from("valid choices:"),
+            ErrorKind::InvalidValue => String::new(),
+            ErrorKind::MissingDependency => String::from("requires:"),
+            _ => String::from("see:")
+        }
+    }
+}
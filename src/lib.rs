@@ -21,6 +21,7 @@ mod token;
 mod usage;
 mod vars;
 
-pub use matches::{Matches, Match, matches};
-pub use vars::{Vars, vars};
+pub use errors::{Error, ErrorKind, Errors};
+pub use matches::{Entry, Matches, Match, Source, matches, matches_all, matches_all_with_env, matches_with_env};
+pub use vars::{Command, Ordering, Vars, vars};
 pub use usage::usage;
\ No newline at end of file
@@ -16,105 +16,628 @@
 */
 
 use std::collections::HashMap;
-use std::collections::hash_map::Keys;
+use std::env;
+use std::error;
+use std::slice::Iter;
+use std::str::FromStr;
 
-use errors::{Error, ErrorKind};
-use vars::Vars;
+use errors::{Error, ErrorKind, Errors};
+use token::Token;
+use vars::{Ordering, Vars};
 
-pub type Matches = HashMap<String, String>;
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Matches {
+    entries: Vec<Entry>,
+    remainder: Vec<String>,
+    subcommand: Option<(String, Box<Matches>)>
+}
+
+// A single option or arg found on the command line, e.g. --colour=auto, or filled in from elsewhere
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub raw: String,
+    pub index: Option<usize>,
+    pub value: Option<String>,
+    pub negated: bool,
+    pub source: Source
+}
+
+// Where an entry's value came from, in order of precedence
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    CommandLine,
+    Environment,
+    ConfigFile,
+    Default
+}
+
+impl Matches {
+    pub fn new() -> Matches {
+        Matches {
+            entries: Vec::new(),
+            remainder: Vec::new(),
+            subcommand: None
+        }
+    }
+
+    // Every entry, in the order in which it was passed on the command line
+    pub fn iter(&self) -> Iter<'_, Entry> {
+        self.entries.iter()
+    }
+
+    pub fn entries(&self, name: &str) -> Vec<&Entry> {
+        self.entries.iter().filter(|e| e.name == name).collect()
+    }
+
+    // The unknown opts passed in lenient mode, exactly as they were passed, e.g. to forward them
+    // on to another program
+    pub fn remainder(&self) -> &[String] {
+        &self.remainder
+    }
+
+    // Values from a config file take precedence over default values, but not over values passed
+    // on the command line or through environment variables. They're only added after matching,
+    // so a required option can't be given by a config file alone
+    pub fn set_config(&mut self, vars: &Vars, name: &str, value: &str, file: &str) -> Result<(), Error> {
+        let token = match vars.get_opt(name) {
+            Some(token) => token,
+            None => return Err(Error::new(ErrorKind::UnknownOption, String::from(name)))
+        };
+
+        // Only options which take an argument can be given a value, just as with defaults
+        if !token.has_arg {
+            return Err(Error::new(ErrorKind::TokenFormat, String::from(name)));
+        }
+        token.check_value(value)?;
+
+        let name = token.name();
+        if self.is_passed(&name) {
+            return Ok(());
+        }
+
+        self.entries.retain(|e| e.name != name || e.source != Source::Default);
+        self.entries.push(Entry {
+            name,
+            raw: String::from(file),
+            index: None,
+            value: Some(String::from(value)),
+            negated: false,
+            source: Source::ConfigFile
+        });
+
+        Ok(())
+    }
+
+    // Default values don't count as being passed, e.g. for conflicting options, and neither do
+    // flags which were last negated, e.g. --json --no-json
+    fn is_passed(&self, name: &str) -> bool {
+        let last = self.entries.iter()
+            .rev()
+            .find(|e| e.name == name && (e.source == Source::CommandLine || e.source == Source::Environment));
+
+        match last {
+            Some(entry) => !entry.negated,
+            None => false
+        }
+    }
+
+    fn insert(&mut self, token: &Token, raw: &str, index: Option<usize>, value: Option<String>, source: Source) {
+        self.entries.push(Entry {
+            name: token.name(),
+            raw: String::from(raw),
+            index,
+            value,
+            negated: false,
+            source
+        });
+    }
+
+    fn insert_flag(&mut self, token: &Token, raw: &str, index: usize, negated: bool) {
+        self.entries.push(Entry {
+            name: token.name(),
+            raw: String::from(raw),
+            index: Some(index),
+            value: None,
+            negated,
+            source: Source::CommandLine
+        });
+    }
+}
+
+pub fn matches(env_args: &[String], vars: &Vars) -> Result<Matches, Error> {
+    matches_with_env(env_args, vars, &env_vars())
+}
+
+// Environment variables that aren't valid unicode can't be matched against, so they're skipped
+fn env_vars() -> HashMap<String, String> {
+    env::vars_os()
+        .filter_map(|(key, value)| match (key.into_string(), value.into_string()) {
+            (Ok(k), Ok(v)) => Some((k, v)),
+            _ => None
+        })
+        .collect()
+}
+
+pub fn matches_with_env(env_args: &[String], vars: &Vars, env_vars: &HashMap<String, String>)
+        -> Result<Matches, Error> {
+    let (matches, mut errors) = parse(env_args, vars, Modes::of(vars), env_vars);
+
+    if errors.is_empty() {
+        Ok(matches)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+pub fn matches_all(env_args: &[String], vars: &Vars) -> Result<Matches, Errors> {
+    matches_all_with_env(env_args, vars, &env_vars())
+}
+
+pub fn matches_all_with_env(env_args: &[String], vars: &Vars, env_vars: &HashMap<String, String>)
+        -> Result<Matches, Errors> {
+    let (matches, errors) = parse(env_args, vars, Modes::of(vars), env_vars);
+
+    if errors.is_empty() {
+        Ok(matches)
+    } else {
+        Err(Errors::new(errors, matches))
+    }
+}
+
+// The modes which the matching is done in. Subcommands are matched in their parent's modes, as
+// well as their own
+#[derive(Clone, Copy)]
+struct Modes {
+    abbreviate: bool,
+    lenient: bool,
+    ordering: Ordering
+}
+
+impl Modes {
+    fn of(vars: &Vars) -> Modes {
+        Modes {
+            abbreviate: vars.abbreviate,
+            lenient: vars.lenient,
+            ordering: vars.ordering
+        }
+    }
+
+    fn inherit(&self, sub_vars: &Vars) -> Modes {
+        Modes {
+            abbreviate: self.abbreviate || sub_vars.abbreviate,
+            lenient: self.lenient || sub_vars.lenient,
+            ordering: match sub_vars.ordering {
+                Ordering::Default => self.ordering,
+                ordering => ordering
+            }
+        }
+    }
+}
 
-pub fn matches(env_args: &[String], vars: &mut Vars) -> Result<Matches, Error> {
-    let mut matches: Matches = HashMap::new();
-    let mut args = env_args.iter();
+// Every error is collected, rather than stopping at the first, so that they can all be reported at once
+fn parse(env_args: &[String], vars: &Vars, modes: Modes, env_vars: &HashMap<String, String>)
+        -> (Matches, Vec<Error>) {
+    let mut matches = Matches::new();
+    let mut errors: Vec<Error> = Vec::new();
+    let mut args = env_args.iter().enumerate();
 
     args.next(); // Remove the program name
 
-    while let Some(mut current_arg) = args.next() {
-        let mut arg_vec: Vec<String> = Vec::new();
+    // Everything following a bare -- is treated as an arg, even if it begins with a dash
+    let mut opts_ended = false;
+
+    // Args are filled in the order in which they were defined. The variadic arg, once reached,
+    // collects all of the remaining args
+    let mut position: usize = 0;
+    let mut variadic: Option<&Token> = None;
+
+    // In POSIX order, the opts end at the first arg, e.g. so that -la is left to ls in `prog exec ls -la`
+    let require_order = match modes.ordering {
+        Ordering::Default => env_vars.contains_key("POSIXLY_CORRECT"),
+        Ordering::Permute => false,
+        Ordering::RequireOrder => true
+    };
+
+    while let Some((index, current_arg)) = args.next() {
+        // The opts as they were passed, their names, and any value attached to them, e.g. --out=file
+        let mut arg_vec: Vec<(String, String, Option<String>)> = Vec::new();
 
-        // Determine if current opt is in short, long, or arg form
-        if &current_arg[..1] == "-" {
-            if &current_arg[..2] == "--" { // Long form opt
-                arg_vec.push(String::from(&current_arg[2..]));
+        if !opts_ended && current_arg == "--" {
+            opts_ended = true;
+            continue;
+        }
+
+        // Determine if current opt is in short, long, or arg form. A lone dash is an arg, e.g. stdin
+        let is_opt = current_arg.starts_with('-') && current_arg != "-";
+        if !opts_ended && is_opt {
+            if let Some(opt) = current_arg.strip_prefix("--") { // Long form opt
+                let (name, value) = match opt.find('=') {
+                    Some(i) => (&opt[..i], Some(String::from(&opt[i + 1..]))),
+                    None => (opt, None)
+                };
+
+                let raw = format!("--{}", name);
+                if !modes.abbreviate {
+                    arg_vec.push((raw, String::from(name), value));
+                } else {
+                    match vars.expand_opt(name) {
+                        Ok(expanded) => arg_vec.push((raw, expanded, value)),
+                        Err(why) => errors.push(why.at(index))
+                    }
+                }
             } else { // Short form opt
                 // Assuming it's a group of short-form vars; e.g. tar -xzf
-                for c in current_arg[1..].chars() {
-                    let mut s = String::new();
-                    s.push(c);
-                    arg_vec.push(s);
+                let opt = &current_arg[1..];
+                for (i, c) in opt.char_indices() {
+                    let name = c.to_string();
+                    let rest = &opt[i + c.len_utf8()..];
+
+                    // Everything following an opt that takes an argument is its value, e.g. -j4
+                    let takes_value = match vars.get_opt(&name) {
+                        Some(token) => token.has_arg,
+                        None => false
+                    };
+
+                    // In lenient mode, the rest of the group following an unknown opt is taken as
+                    // its value, e.g. -Xfoo, unless it's made up of known opts, e.g. -Xv
+                    let unknown_value = modes.lenient && !vars.contains_opt(&name)
+                        && !rest.chars().all(|r| vars.contains_opt(&r.to_string()));
+
+                    let raw = format!("-{}", name);
+                    if (takes_value || unknown_value) && !rest.is_empty() {
+                        arg_vec.push((raw, name, Some(String::from(rest))));
+                        break;
+                    } else {
+                        arg_vec.push((raw, name, None));
+                    }
                 }
             }
 
-            for arg in arg_vec.iter() {
-                if vars.contains_opt(arg) {
-                    let token = vars.get_opt(arg).unwrap();
+            let last = arg_vec.len();
+            for (i, (raw, arg, value)) in arg_vec.into_iter().enumerate() {
+                if vars.contains_opt(&arg) {
+                    let token = vars.get_opt(&arg).unwrap();
 
                     if token.has_arg {
-                        // NOTE: The corresponding arg MUST be immediately following
-                        current_arg = match args.next() {
-                            None =>  return Err(Error::new(ErrorKind::MissingArgument, arg.clone())),
-                            Some(a) => a
+                        // The corresponding arg is either attached, or immediately following
+                        let (value, value_index) = match value {
+                            Some(v) => (v, index),
+                            None => match args.next() {
+                                None => {
+                                    errors.push(Error::new(ErrorKind::MissingValue, raw).at(index));
+                                    continue;
+                                },
+                                Some((i, a)) => (a.clone(), i)
+                            }
                         };
 
-                        matches.insert(token.name(), (*current_arg).clone());
+                        match token.check_value_as(&raw, &value) {
+                            Ok(_) => matches.insert(token, &raw, Some(index), Some(value), Source::CommandLine),
+                            Err(why) => errors.push(why.at(value_index))
+                        }
+                    } else if let Some(v) = value {
+                        // Flags can't be given a value, e.g. --verbose=yes
+                        errors.push(Error::new(ErrorKind::UnexpectedValue, raw).with_value(v).at(index));
                     } else {
-                        matches.insert(token.name(), String::new());
+                        matches.insert_flag(token, &raw, index, token.is_negation(&arg));
                     }
+                } else if modes.lenient {
+                    // Unknown opts are kept as they were passed, along with any value attached to them.
+                    // A following value can't be told apart from an arg, so it's only taken as the
+                    // opt's value when there's no arg or subcommand left for it to be, e.g. --target x86
+                    match value {
+                        Some(v) if raw.starts_with("--") => matches.remainder.push(format!("{}={}", raw, v)),
+                        Some(v) => matches.remainder.push(format!("{}{}", raw, v)),
+                        None => {
+                            matches.remainder.push(raw);
+
+                            let unclaimed = i + 1 == last && variadic.is_none() && vars.get_arg(position).is_none()
+                                && vars.subcommands().len() == 0;
+                            match args.clone().next() {
+                                Some((_, a)) if unclaimed && !a.starts_with('-') => {
+                                    matches.remainder.push(a.clone());
+                                    args.next();
+                                },
+                                _ => ()
+                            }
+                        }
+                    }
+                } else if raw.starts_with("--") {
+                    let suggestions = vars.suggest_opt(&arg);
+                    errors.push(Error::with_related(ErrorKind::UnknownOption, raw, suggestions).at(index));
                 } else {
-                    return Err(Error::new(ErrorKind::InvalidArgument, arg.clone()));
+                    errors.push(Error::new(ErrorKind::UnknownOption, raw).at(index));
                 }
             }
+        } else if !opts_ended && vars.subcommands().len() > 0 {
+            // The first arg picks the subcommand, which parses all of the remaining args itself
+            match vars.get_subcommand(current_arg) {
+                Some(sub_vars) => {
+                    let (sub_matches, sub_errors) = parse(&env_args[index..], sub_vars, modes.inherit(sub_vars), env_vars);
+                    matches.subcommand = Some((current_arg.clone(), Box::new(sub_matches)));
+
+                    // The subcommand's args are indexed from the subcommand itself
+                    for error in sub_errors.into_iter() {
+                        match error.index() {
+                            Some(i) => errors.push(error.at(index + i)),
+                            None => errors.push(error)
+                        }
+                    }
+                },
+                // The remaining args are meant for a subcommand, so they can't be matched either
+                None => {
+                    let suggestions = vars.suggest_subcommand(current_arg);
+                    let error = Error::with_related(ErrorKind::UnknownSubcommand, current_arg.clone(), suggestions);
+                    errors.push(error.at(index));
+                }
+            }
+
+            break;
+        } else if let Some(arg) = variadic {
+            match arg.check_value(current_arg) {
+                Ok(_) => matches.insert(arg, current_arg, Some(index), Some(current_arg.clone()), Source::CommandLine),
+                Err(why) => errors.push(why.at(index))
+            }
         } else { // Probably a required arg
-            let arg = vars.get_arg().unwrap();
-            matches.insert(arg.name(), (*current_arg).clone());
+            match vars.get_arg(position) {
+                Some(arg) => {
+                    position += 1;
+                    if arg.is_variadic {
+                        variadic = Some(arg);
+                    }
+
+                    match arg.check_value(current_arg) {
+                        Ok(_) => matches.insert(arg, current_arg, Some(index), Some(current_arg.clone()), Source::CommandLine),
+                        Err(why) => errors.push(why.at(index))
+                    }
+                },
+                None => errors.push(Error::new(ErrorKind::UnexpectedPositional, current_arg.clone()).at(index))
+            }
+        }
+
+        if require_order && !is_opt {
+            opts_ended = true;
+        }
+    }
+
+    // Any remaining args must be optional, in which case they take their default value, if any
+    while let Some(arg) = vars.get_arg(position) {
+        position += 1;
+        if !arg.is_optional {
+            errors.push(Error::new(ErrorKind::MissingPositional, arg.name()));
+        } else if let Some(ref default) = arg.default {
+            matches.insert(arg, "", None, Some(default.clone()), Source::Default);
+        }
+    }
+
+    // Options that weren't passed fall back to their environment variable, then their default value
+    for token in vars.tokens() {
+        if token.is_arg || matches.has_match(&token.name()) {
+            continue;
+        }
+
+        let env_value = match token.env {
+            Some(ref env) => env_vars.get(env).map(|value| (env, value)),
+            None => None
+        };
+
+        if let Some((env, value)) = env_value {
+            match token.check_value(value) {
+                Ok(_) => matches.insert(token, env, None, Some(value.clone()), Source::Environment),
+                Err(why) => errors.push(why)
+            }
+        } else if let Some(ref default) = token.default {
+            matches.insert(token, "", None, Some(default.clone()), Source::Default);
+        } else if token.is_required && !matches.has_match("help") {
+            // Asking for the usage doesn't need the required options
+            errors.push(Error::new(ErrorKind::MissingOption, token.flag_name()));
         }
     }
 
-    match vars.arg_len() {
-        0 => Ok( matches ),
-        _ => Err(Error::new(ErrorKind::MissingArgument, vars.get_arg().unwrap().name())),
+    // Only one option of each conflicting group may be passed, either on the command line or
+    // through its environment variable
+    for group in vars.conflict_groups() {
+        let mut passed = group.iter().filter(|t| matches.is_passed(&t.name())).map(|t| t.flag_name());
+
+        if let Some(offender) = passed.next() {
+            let others: Vec<String> = passed.collect();
+            if !others.is_empty() {
+                errors.push(Error::with_related(ErrorKind::ConflictingOptions, offender, others));
+            }
+        }
     }
+
+    // Options that were passed must be accompanied by the options they require, which may also be
+    // passed through their environment variables
+    for (token, required) in vars.requirements() {
+        if matches.is_passed(&token.name()) {
+            let missing: Vec<String> = required.iter()
+                .filter(|t| !matches.is_passed(&t.name()))
+                .map(|t| t.flag_name())
+                .collect();
+
+            if !missing.is_empty() {
+                errors.push(Error::with_related(ErrorKind::MissingDependency, token.flag_name(), missing));
+            }
+        }
+    }
+
+    (matches, errors)
 }
 
 pub trait Match {
     fn get(&self, arg: &str) -> Option<&String>;
 
+    fn get_all(&self, arg: &str) -> Vec<&String>;
+
+    fn get_as<T: FromStr>(&self, arg: &str) -> Result<T, Error>
+        where T::Err: error::Error + Send + Sync + 'static;
+
+    fn get_or<T: FromStr>(&self, arg: &str, default: T) -> Result<T, Error>
+        where T::Err: error::Error + Send + Sync + 'static;
+
+    fn get_all_as<T: FromStr>(&self, arg: &str) -> Result<Vec<T>, Error>
+        where T::Err: error::Error + Send + Sync + 'static;
+
+    fn occurrences(&self, arg: &str) -> usize;
+
+    fn count(&self, arg: &str) -> usize;
+
     fn has_match(&self, arg: &str) -> bool;
 
-    fn matches(&self) -> Keys<String, String>;
+    fn flag(&self, arg: &str) -> Option<bool>;
+
+    fn matches(&self) -> Vec<&String>;
+
+    fn source(&self, arg: &str) -> Option<Source>;
+
+    fn subcommand(&self) -> Option<(&str, &Matches)>;
 }
 
+// The value of a flag which was passed, e.g. for get()
+static NO_VALUE: String = String::new();
+
 impl Match for Matches {
+    // Flags don't have a value, so an empty one is returned for them, as it always has been
     fn get(&self, arg: &str) -> Option<&String> {
-        self.get(arg)
+        self.entries.iter()
+            .rev()
+            .find(|e| e.name == arg)
+            .map(|e| e.value.as_ref().unwrap_or(&NO_VALUE))
+    }
+
+    fn get_all(&self, arg: &str) -> Vec<&String> {
+        self.entries.iter()
+            .filter(|e| e.name == arg)
+            .filter_map(|e| e.value.as_ref())
+            .collect()
+    }
+
+    fn get_as<T: FromStr>(&self, arg: &str) -> Result<T, Error>
+        where T::Err: error::Error + Send + Sync + 'static {
+        match self.entries.iter().rev().find(|e| e.name == arg) {
+            Some(entry) => parse_value(entry),
+            None => Err(Error::new(ErrorKind::NoMatch, String::from(arg)))
+        }
+    }
+
+    fn get_or<T: FromStr>(&self, arg: &str, default: T) -> Result<T, Error>
+        where T::Err: error::Error + Send + Sync + 'static {
+        match self.entries.iter().rev().find(|e| e.name == arg) {
+            Some(entry) => parse_value(entry),
+            None => Ok(default)
+        }
+    }
+
+    fn get_all_as<T: FromStr>(&self, arg: &str) -> Result<Vec<T>, Error>
+        where T::Err: error::Error + Send + Sync + 'static {
+        self.entries.iter()
+            .filter(|e| e.name == arg && e.value.is_some())
+            .map(parse_value)
+            .collect()
+    }
+
+    fn occurrences(&self, arg: &str) -> usize {
+        self.entries.iter().filter(|e| e.name == arg).count()
+    }
+
+    // Flags don't take a value, so each occurrence is a count, e.g. -vvv or -v -vv. Negations,
+    // e.g. --no-verbose, aren't counted
+    fn count(&self, arg: &str) -> usize {
+        self.entries.iter().filter(|e| e.name == arg && e.value.is_none() && !e.negated).count()
     }
 
     fn has_match(&self, arg: &str) -> bool {
-        let arg = String::from(arg);
-        self.contains_key(&arg)
+        self.entries.iter().any(|e| e.name == arg)
     }
 
-    fn matches(&self) -> Keys<String, String> {
-        self.keys()
+    // The last occurrence of a flag wins, e.g. --color --no-color
+    fn flag(&self, arg: &str) -> Option<bool> {
+        self.entries.iter()
+            .rev()
+            .find(|e| e.name == arg && e.value.is_none())
+            .map(|e| !e.negated)
+    }
+
+    // The name of every match, in the order in which it was first passed
+    fn matches(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = Vec::new();
+        for entry in self.entries.iter() {
+            if !names.contains(&&entry.name) {
+                names.push(&entry.name);
+            }
+        }
+
+        names
+    }
+
+    fn source(&self, arg: &str) -> Option<Source> {
+        self.entries.iter().rev().find(|e| e.name == arg).map(|e| e.source)
+    }
+
+    fn subcommand(&self) -> Option<(&str, &Matches)> {
+        match self.subcommand {
+            Some((ref name, ref sub_matches)) => Some((name, sub_matches)),
+            None => None
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(entry: &Entry) -> Result<T, Error>
+        where T::Err: error::Error + Send + Sync + 'static {
+    let value = entry.value.as_ref().unwrap_or(&NO_VALUE);
+
+    match value.parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(why) => {
+            // The flag is blamed as it was typed, e.g. -r, unless the value wasn't passed along
+            // with one, e.g. for args, which are passed as only their value
+            let offender = if entry.source == Source::CommandLine && entry.raw != *value {
+                entry.raw.clone()
+            } else {
+                entry.name.clone()
+            };
+
+            let error = Error::with_related(ErrorKind::InvalidValue, offender, vec![why.to_string()]);
+            Err(error.with_value(value.clone()).caused_by(Box::new(why)))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Match, matches};
-    use super::super::vars::vars;
+    use std::collections::HashMap;
+    use std::error;
+    use std::num::ParseIntError;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::{Match, Matches, Source, matches_all_with_env, matches_with_env};
+    use super::super::errors::{Error, ErrorKind, Errors};
+    use super::super::vars::{Ordering, Vars, vars};
+
+    // The tests mustn't depend on the environment they're run in, e.g. on POSIXLY_CORRECT
+    fn matches(env_args: &[String], vars: &Vars) -> Result<Matches, Error> {
+        matches_with_env(env_args, vars, &HashMap::new())
+    }
+
+    fn matches_all(env_args: &[String], vars: &Vars) -> Result<Matches, Errors> {
+        matches_all_with_env(env_args, vars, &HashMap::new())
+    }
     
     #[test]
+    #[allow(clippy::redundant_pattern_matching, clippy::bool_assert_comparison)]
     fn test_matches_good() {
         let env_args = vec![String::from("test"), String::from("-a"), String::from("Test")];
         let opts = vec!["o/opt#An option", "a#An argument:"];
         
-        let mut var = match vars("Test", &opts) {
+        let var = match vars("Test", &opts) {
             Ok(m) => m,
             Err(why) => panic!("An error occurred: {}", why)
         };
         
-        let matches = matches(&env_args, &mut var).unwrap();
+        let matches = matches(&env_args, &var).unwrap();
         
         let has_opt = match matches.get("opt") {
             Some(_) => true,
@@ -125,14 +648,857 @@ mod tests {
         assert_eq!(has_opt, false);
     }
 
+    #[test]
+    fn test_matches_repeated() {
+        let env_args = vec![String::from("test"), String::from("-I"), String::from("a"),
+                            String::from("--include"), String::from("b"), String::from("-I"),
+                            String::from("c")];
+        let opts = vec!["I/include#An include path:"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert_eq!(matches.occurrences("include"), 3);
+        assert_eq!(matches.get_all("include"), vec!["a", "b", "c"]);
+        assert_eq!(matches.get("include").unwrap(), "c");
+        assert_eq!(matches.occurrences("help"), 0);
+        assert!(matches.get_all("help").is_empty());
+    }
+
+    #[test]
+    fn test_matches_attached_values() {
+        let env_args: Vec<String> = ["test", "--output=out.txt", "-j4", "-xzfarchive.tar", "--define=a=b"]
+            .iter().map(|s| s.to_string()).collect();
+        let opts = vec!["o/output#Output file:", "j/jobs#Number of jobs:", "x#Extract", "z#Gzip",
+                        "f/file#Archive file:", "D/define#A definition:"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert_eq!(matches.get("output").unwrap(), "out.txt");
+        assert_eq!(matches.get("jobs").unwrap(), "4");
+        assert!(matches.has_match("x"));
+        assert!(matches.has_match("z"));
+        assert_eq!(matches.get("file").unwrap(), "archive.tar");
+        assert_eq!(matches.get("define").unwrap(), "a=b");
+    }
+
+    #[test]
+    fn test_matches_flag_with_value() {
+        let env_args = vec![String::from("test"), String::from("--verbose=yes")];
+        let opts = vec!["v/verbose#Verbose output"];
+
+        let vars = vars("Test", &opts).unwrap();
+        assert!(matches(&env_args, &vars).is_err());
+    }
+
+    #[test]
+    fn test_matches_end_of_opts() {
+        let env_args: Vec<String> = ["test", "-v", "--", "-file", "--"]
+            .iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Verbose output", ":first#The first file", ":second#The second file"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert!(matches.has_match("verbose"));
+        assert_eq!(matches.get("first").unwrap(), "-file");
+        assert_eq!(matches.get("second").unwrap(), "--");
+    }
+
+    #[test]
+    fn test_matches_stdin_arg() {
+        let env_args: Vec<String> = ["test", "-", "-v"].iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Verbose output", ":input#The input file"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert!(matches.has_match("verbose"));
+        assert_eq!(matches.get("input").unwrap(), "-");
+    }
+
+    #[test]
+    fn test_matches_variadic() {
+        let env_args: Vec<String> = ["test", "out", "a", "-v", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Verbose output", ":dest#The destination", ":files...#Files to copy"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert!(matches.has_match("verbose"));
+        assert_eq!(matches.get("dest").unwrap(), "out");
+        assert_eq!(matches.get_all("files"), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_matches_unexpected_arg() {
+        let env_args: Vec<String> = ["test", "a", "b"].iter().map(|s| s.to_string()).collect();
+        let opts = vec![":file#A file"];
+
+        let vars = vars("Test", &opts).unwrap();
+        assert!(matches(&env_args, &vars).is_err());
+    }
+
+    #[test]
+    fn test_matches_optional_args() {
+        let opts = vec![":src#The source", ":dest=out#The destination", ":mode?#The copy mode"];
+
+        {
+            let env_args: Vec<String> = ["test", "in"].iter().map(|s| s.to_string()).collect();
+            let vars = vars("Test", &opts).unwrap();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.get("src").unwrap(), "in");
+            assert_eq!(matches.get("dest").unwrap(), "out");
+            assert!(!matches.has_match("mode"));
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "in", "there", "fast"].iter().map(|s| s.to_string()).collect();
+            let vars = vars("Test", &opts).unwrap();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.get("dest").unwrap(), "there");
+            assert_eq!(matches.get("mode").unwrap(), "fast");
+        }
+
+        {
+            let env_args = vec![String::from("test")];
+            let vars = vars("Test", &opts).unwrap();
+            assert!(matches(&env_args, &vars).is_err());
+        }
+    }
+
+    #[test]
+    fn test_matches_default_opts() {
+        let opts = vec!["a/addend=1#The addend:", "o/output#The output file:", "f/format#The format:"];
+        let env_args: Vec<String> = ["test", "-f", "json"].iter().map(|s| s.to_string()).collect();
+
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.set_default("format", "yaml").unwrap();
+        vars.set_default("o", "out.txt").unwrap();
+        assert!(vars.set_default("help", "yes").is_err());
+        assert!(vars.set_default("missing", "yes").is_err());
+
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert_eq!(matches.get("addend").unwrap(), "1");
+        assert_eq!(matches.get("output").unwrap(), "out.txt");
+        assert_eq!(matches.get_all("format"), vec!["json"]);
+    }
+
+    #[test]
+    fn test_matches_env_opts() {
+        let opts = vec!["o/output=out.txt$APP_OUTPUT#The output file:", "l/log$APP_LOG#The log file:",
+                        "f/format=json$APP_FORMAT#The format:"];
+        let env_args: Vec<String> = ["test", "--format", "yaml"].iter().map(|s| s.to_string()).collect();
+        let mut env_vars = HashMap::new();
+        env_vars.insert(String::from("APP_OUTPUT"), String::from("env.txt"));
+        env_vars.insert(String::from("APP_FORMAT"), String::from("toml"));
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches_with_env(&env_args, &vars, &env_vars).unwrap();
+
+        assert_eq!(matches.get("output").unwrap(), "env.txt");
+        assert_eq!(matches.get("format").unwrap(), "yaml");
+        assert!(!matches.has_match("log"));
+    }
+
+    #[test]
+    fn test_matches_subcommands() {
+        let mut with_args = vars("Test", &[":file#A file"]).unwrap();
+        assert!(with_args.subcommand("build", "Build the project", &[]).is_err());
+
+        let opts = vec!["v/verbose#Verbose output"];
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.subcommand("build", "Build the project", &["r/release#Build in release mode"]).unwrap();
+        vars.subcommand("test", "Run the tests", &["f/filter#Only run matching tests:", ":name?#A test"]).unwrap();
+        assert!(vars.subcommand("test", "Run the tests again", &[]).is_err());
+
+        {
+            let env_args: Vec<String> = ["test", "-v", "test", "--filter", "x", "-v"]
+                .iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_err());
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "-v", "build", "--release"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert!(matches.has_match("verbose"));
+            assert!(!matches.has_match("release"));
+
+            let (name, sub_matches) = matches.subcommand().unwrap();
+            assert_eq!(name, "build");
+            assert!(sub_matches.has_match("release"));
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "test", "-f", "x", "one"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            let (name, sub_matches) = matches.subcommand().unwrap();
+            assert_eq!(name, "test");
+            assert_eq!(sub_matches.get("filter").unwrap(), "x");
+            assert_eq!(sub_matches.get("name").unwrap(), "one");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "deploy"].iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_err());
+        }
+
+        {
+            let env_args = vec![String::from("test")];
+            let matches = matches(&env_args, &vars).unwrap();
+            assert!(matches.subcommand().is_none());
+        }
+
+        // Everything following -- is an arg, even if it names a subcommand
+        {
+            let env_args: Vec<String> = ["test", "--", "build"].iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnexpectedPositional);
+        }
+    }
+
+    #[test]
+    fn test_matches_subcommand_modes() {
+        // Subcommands are matched in their parent's modes, even those set after they were added
+        {
+            let mut vars = vars("Test", &["v/verbose#Verbose output"]).unwrap();
+            vars.subcommand("build", "Build the project", &["r/release#Build in release mode"]).unwrap();
+
+            let env_args: Vec<String> = ["test", "build", "--rel", "--zzz"].iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_err());
+
+            vars.abbreviate = true;
+            vars.lenient = true;
+            let matches = matches(&env_args, &vars).unwrap();
+
+            let (_, sub_matches) = matches.subcommand().unwrap();
+            assert!(sub_matches.has_match("release"));
+            assert_eq!(sub_matches.remainder(), &["--zzz"]);
+        }
+
+        // As well as in their own
+        {
+            let mut vars = vars("Test", &["v/verbose#Verbose output"]).unwrap();
+            vars.subcommand("exec", "Run a command", &[":command...?#The command"]).unwrap().lenient = true;
+
+            let env_args: Vec<String> = ["test", "--zzz", "exec"].iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_err());
+
+            let env_args: Vec<String> = ["test", "exec", "--zzz"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+            assert_eq!(matches.subcommand().unwrap().1.remainder(), &["--zzz"]);
+        }
+    }
+
+    #[test]
+    fn test_matches_abbreviations() {
+        let opts = vec!["v/verbose#Verbose output", "/version#Print the version", "o/output#The output file:"];
+        let mut vars = vars("Test", &opts).unwrap();
+
+        {
+            let env_args: Vec<String> = ["test", "--verb"].iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_err());
+        }
+
+        vars.abbreviate = true;
+
+        {
+            let env_args: Vec<String> = ["test", "--verb", "--out=a", "--o", "b", "--version"]
+                .iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert!(matches.has_match("verbose"));
+            assert!(matches.has_match("version"));
+            assert_eq!(matches.get_all("output"), vec!["a", "b"]);
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--ver"].iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "An ambiguous option was passed to the program: --ver (could be: --verbose, --version)");
+        }
+
+        // An empty name doesn't abbreviate every option, it's just unknown
+        {
+            let env_args: Vec<String> = ["test", "--=x"].iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!((error.kind(), error.offender()), (ErrorKind::UnknownOption, "--"));
+        }
+    }
+
+    #[test]
+    fn test_matches_counted_flags() {
+        let env_args: Vec<String> = ["test", "-v", "-qvv", "--verbose", "-q"].iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Increase verbosity", "q#Decrease verbosity", "d/debug#Debug output"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert_eq!(matches.count("verbose"), 4);
+        assert_eq!(matches.count("q"), 2);
+        assert_eq!(matches.count("debug"), 0);
+
+        // Flags that were passed have an empty value
+        assert_eq!(matches.get("verbose").unwrap(), "");
+        assert!(matches.get("debug").is_none());
+        assert!(matches.get_all("verbose").is_empty());
+    }
+
+    #[test]
+    fn test_matches_negatable_flags() {
+        let opts = vec!["c/[no-]color#Colorize the output", "/[no-]pager#Use a pager", "/[no-]wrap#Wrap lines",
+                        "o/output#The output file:"];
+        let vars = vars("Test", &opts).unwrap();
+
+        {
+            let env_args: Vec<String> = ["test", "--color", "--no-pager", "-o", "out", "--no-color", "--pager", "-c"]
+                .iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.flag("color"), Some(true));
+            assert_eq!(matches.flag("pager"), Some(true));
+            assert_eq!(matches.flag("wrap"), None);
+            assert_eq!(matches.flag("output"), None);
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "-c", "--no-color"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.flag("color"), Some(false));
+            assert!(matches.has_match("color"));
+            assert_eq!(matches.count("color"), 1);
+            assert_eq!(matches.occurrences("color"), 2);
+        }
+    }
+
+    #[test]
+    fn test_matches_aliases() {
+        let opts = vec!["q,s/quiet,silent#Suppress output", "/[no-]color,[no-]colour#Colorize the output"];
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.abbreviate = true;
+
+        {
+            let env_args: Vec<String> = ["test", "-s", "--silent", "-q", "--colour", "--no-color"]
+                .iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.count("quiet"), 3);
+            assert!(!matches.has_match("silent"));
+            assert_eq!(matches.flag("color"), Some(false));
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--col"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.flag("color"), Some(true));
+        }
+    }
+
+    #[test]
+    fn test_matches_required_opts() {
+        let opts = vec!["c/config!$APP_CONFIG#The configuration file:", "v/verbose#Verbose output"];
+        let mut vars = vars("Test", &opts).unwrap();
+        assert!(vars.conflicts(&["config", "verbose"]).is_err());
+
+        {
+            let env_args: Vec<String> = ["test", "-v"].iter().map(|s| s.to_string()).collect();
+            let error = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap_err();
+            assert_eq!(error.to_string(), "A required option is missing: --config");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--config=app.toml"].iter().map(|s| s.to_string()).collect();
+            let matches = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap();
+            assert_eq!(matches.get("config").unwrap(), "app.toml");
+        }
+
+        {
+            let env_args = vec![String::from("test")];
+            let mut env_vars = HashMap::new();
+            env_vars.insert(String::from("APP_CONFIG"), String::from("env.toml"));
+
+            let matches = matches_with_env(&env_args, &vars, &env_vars).unwrap();
+            assert_eq!(matches.get("config").unwrap(), "env.toml");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--help"].iter().map(|s| s.to_string()).collect();
+            let matches = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap();
+            assert!(matches.has_match("help"));
+            assert!(!matches.has_match("config"));
+        }
+    }
+
+    #[test]
+    fn test_matches_conflicts() {
+        let opts = vec!["/[no-]json#Output JSON", "/yaml#Output YAML", "/toml#Output TOML",
+                        "o/output=-$APP_OUTPUT#The output file:"];
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.conflicts(&["json", "yaml", "toml"]).unwrap();
+        vars.conflicts(&["output", "toml"]).unwrap();
+        assert!(vars.conflicts(&["json", "xml"]).is_err());
+
+        {
+            let env_args: Vec<String> = ["test", "--yaml", "--json"].iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "Conflicting options were passed to the program: --json (conflicts with: --yaml)");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--toml"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+            assert!(matches.has_match("toml"));
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--toml", "-o", "out"].iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_err());
+        }
+
+        // Negated flags don't conflict
+        {
+            let env_args: Vec<String> = ["test", "--json", "--no-json", "--yaml"].iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+            assert_eq!(matches.flag("json"), Some(false));
+        }
+
+        // Options passed through environment variables conflict too, but default values don't
+        {
+            let env_args: Vec<String> = ["test", "--toml"].iter().map(|s| s.to_string()).collect();
+            let mut env_vars = HashMap::new();
+            env_vars.insert(String::from("APP_OUTPUT"), String::from("out"));
+
+            let error = matches_with_env(&env_args, &vars, &env_vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "Conflicting options were passed to the program: --output (conflicts with: --toml)");
+        }
+    }
+
+    #[test]
+    fn test_matches_requirements() {
+        let opts = vec!["/[no-]tls#Use TLS", "k/key-file$APP_KEY#The private key:", "c/cert$APP_CERT#The certificate:",
+                        "/[no-]dry-run#Don't do anything", ":host?#The host"];
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.requires("key-file", &["tls", "c"]).unwrap();
+        vars.requires("dry-run", &["host"]).unwrap();
+        assert!(vars.requires("key-file", &["ssl"]).is_err());
+
+        {
+            let env_args: Vec<String> = ["test", "-k", "key.pem", "--cert=cert.pem"]
+                .iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "An option was passed without the options it requires: --key-file (requires: --tls)");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "-k", "key.pem", "--tls", "-c", "cert.pem", "--dry-run", "localhost"]
+                .iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+            assert_eq!(matches.get("key-file").unwrap(), "key.pem");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--dry-run"].iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "An option was passed without the options it requires: --dry-run (requires: host)");
+        }
+
+        // Negated flags neither meet requirements, nor have to meet their own
+        {
+            let env_args: Vec<String> = ["test", "-k", "key.pem", "-c", "cert.pem", "--no-tls"]
+                .iter().map(|s| s.to_string()).collect();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "An option was passed without the options it requires: --key-file (requires: --tls)");
+
+            let env_args: Vec<String> = ["test", "--no-dry-run"].iter().map(|s| s.to_string()).collect();
+            assert!(matches(&env_args, &vars).is_ok());
+        }
+
+        // Options passed through their environment variables must meet, and may meet, requirements too
+        {
+            let mut env_vars = HashMap::new();
+            env_vars.insert(String::from("APP_KEY"), String::from("key.pem"));
+
+            let env_args = vec![String::from("test")];
+            let error = matches_with_env(&env_args, &vars, &env_vars).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "An option was passed without the options it requires: --key-file (requires: --tls, --cert)");
+
+            env_vars.insert(String::from("APP_CERT"), String::from("cert.pem"));
+            let env_args: Vec<String> = ["test", "--tls"].iter().map(|s| s.to_string()).collect();
+            let matches = matches_with_env(&env_args, &vars, &env_vars).unwrap();
+            assert_eq!(matches.get("cert").unwrap(), "cert.pem");
+        }
+    }
+
+    #[test]
+    fn test_matches_choices() {
+        let opts = vec!["f/format=json@json|yaml|toml$APP_FORMAT#The output format:", ":mode?@fast|slow#The mode"];
+
+        {
+            let mut vars = vars("Test", &opts).unwrap();
+            assert!(vars.set_default("format", "xml").is_err());
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "-fyaml", "slow"].iter().map(|s| s.to_string()).collect();
+            let vars = vars("Test", &opts).unwrap();
+            let matches = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap();
+
+            assert_eq!(matches.get("format").unwrap(), "yaml");
+            assert_eq!(matches.get("mode").unwrap(), "slow");
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "--format", "xml"].iter().map(|s| s.to_string()).collect();
+            let vars = vars("Test", &opts).unwrap();
+            assert!(matches_with_env(&env_args, &vars, &HashMap::new()).is_err());
+        }
+
+        {
+            let env_args: Vec<String> = ["test", "medium"].iter().map(|s| s.to_string()).collect();
+            let vars = vars("Test", &opts).unwrap();
+            let error = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap_err();
+            assert_eq!(error.to_string(),
+                       "An invalid value was passed to the program: mode=medium (valid choices: fast, slow)");
+        }
+
+        {
+            let env_args = vec![String::from("test")];
+            let mut env_vars = HashMap::new();
+            env_vars.insert(String::from("APP_FORMAT"), String::from("ini"));
+
+            let vars = vars("Test", &opts).unwrap();
+            assert!(matches_with_env(&env_args, &vars, &env_vars).is_err());
+        }
+    }
+
+    #[test]
+    fn test_matches_typed_values() {
+        let env_args: Vec<String> = ["test", "-j4", "-n", "1", "-n", "2", "--ratio=half"]
+            .iter().map(|s| s.to_string()).collect();
+        let opts = vec!["j/jobs#Number of jobs:", "n/number#A number:", "r/ratio#A ratio:", "t/timeout#A timeout:"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert_eq!(matches.get_as::<u32>("jobs").unwrap(), 4);
+        assert_eq!(matches.get_all_as::<i64>("number").unwrap(), vec![1, 2]);
+        assert_eq!(matches.get_or::<u64>("timeout", 30).unwrap(), 30);
+        assert_eq!(matches.get_or::<u64>("jobs", 30).unwrap(), 4);
+        assert!(matches.get_as::<u32>("timeout").is_err());
+        assert_eq!(matches.get_as::<f64>("ratio").unwrap_err().to_string(),
+                   "A value could not be parsed: --ratio=half (invalid float literal)");
+
+        let error = matches.get_as::<bool>("jobs").unwrap_err();
+        assert_eq!((error.offender(), error.value()), ("-j", Some("4")));
+    }
+
+    #[test]
+    fn test_matches_entries() {
+        let env_args: Vec<String> = ["test", "in", "--colour", "-o", "", "-v", "--output=out", "--no-color"]
+            .iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Verbose output", "o/output#The output file:", "/[no-]color,[no-]colour#Colorize",
+                        "l/level=1#The level:", ":input#The input file"];
+
+        let vars = vars("Test", &opts).unwrap();
+        let matches = matches(&env_args, &vars).unwrap();
+
+        assert_eq!(matches.matches(), vec!["input", "color", "output", "verbose", "level"]);
+
+        let raw: Vec<&str> = matches.iter().map(|e| e.raw.as_str()).collect();
+        assert_eq!(raw, vec!["in", "--colour", "-o", "-v", "--output", "--no-color", ""]);
+
+        let indices: Vec<Option<usize>> = matches.iter().map(|e| e.index).collect();
+        assert_eq!(indices, vec![Some(1), Some(2), Some(3), Some(5), Some(6), Some(7), None]);
+
+        assert_eq!(matches.get_all("output"), vec!["", "out"]);
+        assert_eq!(matches.get("verbose").unwrap(), "");
+        assert_eq!(matches.entries("verbose")[0].value, None);
+        assert!(matches.has_match("verbose"));
+        assert!(matches.entries("color")[1].negated);
+        assert_eq!(matches.flag("color"), Some(false));
+    }
+
+    #[test]
+    fn test_matches_sources() {
+        let opts = vec!["o/output=out.txt#The output file:", "l/log$APP_LOG#The log file:",
+                        "f/format=json@json|yaml#The format:", "t/threads=1#The number of threads:",
+                        "v/verbose#Verbose output", ":input=-#The input file"];
+        let env_args: Vec<String> = ["test", "-v", "--output", "a.txt"].iter().map(|s| s.to_string()).collect();
+        let mut env_vars = HashMap::new();
+        env_vars.insert(String::from("APP_LOG"), String::from("app.log"));
+
+        let vars = vars("Test", &opts).unwrap();
+        let mut matches = matches_with_env(&env_args, &vars, &env_vars).unwrap();
+        matches.set_config(&vars, "output", "b.txt", "app.toml").unwrap();
+        matches.set_config(&vars, "log", "config.log", "app.toml").unwrap();
+        matches.set_config(&vars, "t", "4", "app.toml").unwrap();
+        assert!(matches.set_config(&vars, "format", "xml", "app.toml").is_err());
+        assert!(matches.set_config(&vars, "missing", "1", "app.toml").is_err());
+        assert_eq!(matches.set_config(&vars, "verbose", "yes", "app.toml").unwrap_err().kind(), ErrorKind::TokenFormat);
+
+        assert_eq!(matches.source("verbose"), Some(Source::CommandLine));
+        assert_eq!(matches.source("output"), Some(Source::CommandLine));
+        assert_eq!(matches.source("log"), Some(Source::Environment));
+        assert_eq!(matches.source("format"), Some(Source::Default));
+        assert_eq!(matches.source("threads"), Some(Source::ConfigFile));
+        assert_eq!(matches.source("input"), Some(Source::Default));
+        assert_eq!(matches.source("missing"), None);
+
+        assert_eq!(matches.get("output").unwrap(), "a.txt");
+        assert_eq!(matches.get("log").unwrap(), "app.log");
+        assert_eq!(matches.get_all("threads"), vec!["4"]);
+        assert_eq!(matches.entries("threads")[0].raw, "app.toml");
+    }
+
+    #[test]
+    fn test_matches_reuse_vars() {
+        let opts = vec!["v/verbose#Verbose output", ":src#The source", ":dest?#The destination"];
+        let vars = Arc::new(vars("Test", &opts).unwrap());
+
+        let handles: Vec<_> = (0..4).map(|i| {
+            let vars = vars.clone();
+            thread::spawn(move || {
+                let env_args = vec![String::from("test"), format!("src{}", i), format!("dest{}", i)];
+                let matches = matches(&env_args, &vars).unwrap();
+                assert_eq!(matches.get("dest").unwrap(), &format!("dest{}", i));
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let env_args: Vec<String> = ["test", "-v", "a", "b"].iter().map(|s| s.to_string()).collect();
+        for _ in 0..2 {
+            let matches = matches(&env_args, &vars).unwrap();
+            assert_eq!(matches.get("src").unwrap(), "a");
+            assert_eq!(matches.get("dest").unwrap(), "b");
+        }
+
+        assert_eq!(vars.arg_len(), 2);
+    }
+
+    #[test]
+    fn test_matches_all_errors() {
+        let opts = vec!["v/verbose#Verbose output", "f/format@json|yaml#The format:", "o/output#The output file:",
+                        ":input#The input file"];
+        let env_args: Vec<String> = ["test", "-x", "--verbsoe", "--format=xml", "-v", "-o"]
+            .iter().map(|s| s.to_string()).collect();
+
+        let vars = vars("Test", &opts).unwrap();
+        let errors = matches_all(&env_args, &vars).unwrap_err();
+
+        assert_eq!(errors.errors().len(), 5);
+        assert!(errors.matches().has_match("verbose"));
+        assert_eq!(errors.to_string(), "5 errors occurred:\n\
+            \x20 An invalid option was passed to the program: -x\n\
+            \x20 An invalid option was passed to the program: --verbsoe (did you mean `--verbose`?)\n\
+            \x20 An invalid value was passed to the program: --format=xml (valid choices: json, yaml)\n\
+            \x20 An option is missing its value: -o\n\
+            \x20 A required argument is missing: input");
+
+        let first = matches(&env_args, &vars).unwrap_err();
+        assert_eq!(first.to_string(), "An invalid option was passed to the program: -x");
+    }
+
+    #[test]
+    fn test_matches_suggestions() {
+        let opts = vec!["v/verbose#Verbose output", "/version#Show the version", "/[no-]color#Colored output"];
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.subcommand("build", "Build the project", &[]).unwrap();
+
+        let error_for = |arg: &str| {
+            let env_args: Vec<String> = vec![String::from("test"), String::from(arg)];
+            matches(&env_args, &vars).unwrap_err().to_string()
+        };
+
+        assert_eq!(error_for("--verbsoe"),
+                   "An invalid option was passed to the program: --verbsoe (did you mean `--verbose`?)");
+        assert_eq!(error_for("--verison"),
+                   "An invalid option was passed to the program: --verison (did you mean `--version`?)");
+        assert_eq!(error_for("--verbosf"),
+                   "An invalid option was passed to the program: --verbosf (did you mean `--verbose`?)");
+        assert_eq!(error_for("--no-colr"),
+                   "An invalid option was passed to the program: --no-colr (did you mean `--no-color`?)");
+        assert_eq!(error_for("--frobnicate"), "An invalid option was passed to the program: --frobnicate");
+        assert_eq!(error_for("-x"), "An invalid option was passed to the program: -x");
+        assert_eq!(error_for("biuld"),
+                   "An invalid subcommand was passed to the program: biuld (did you mean `build`?)");
+        assert_eq!(error_for("deploy"), "An invalid subcommand was passed to the program: deploy");
+    }
+
+    #[test]
+    fn test_matches_error_details() {
+        let opts = vec!["v/verbose#Verbose output", "o/output#The output file:", "f/format@json|yaml#The format:"];
+        let mut vars = vars("Test", &opts).unwrap();
+        vars.subcommand("build", "Build the project", &["j/jobs#Number of jobs:", ":target#The target"]).unwrap();
+
+        let error_for = |args: &[&str]| {
+            let env_args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+            matches(&env_args, &vars).unwrap_err()
+        };
+
+        let error = error_for(&["test", "-v", "--verbsoe"]);
+        assert_eq!(error.kind(), ErrorKind::UnknownOption);
+        assert_eq!(error.offender(), "--verbsoe");
+        assert_eq!(error.related(), &[String::from("--verbose")]);
+        assert_eq!(error.index(), Some(2));
+
+        let error = error_for(&["test", "-o"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::MissingValue, Some(1)));
+        assert_eq!(error.offender(), "-o");
+
+        let error = error_for(&["test", "--verbose=yes"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::UnexpectedValue, Some(1)));
+        assert_eq!((error.offender(), error.value()), ("--verbose", Some("yes")));
+
+        // The offender is always the flag as it was typed, with the value at fault kept apart
+        let error = error_for(&["test", "-f", "xml"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::InvalidChoice, Some(2)));
+        assert_eq!((error.offender(), error.value()), ("-f", Some("xml")));
+        assert_eq!(error.related(), &[String::from("json"), String::from("yaml")]);
+
+        let error = error_for(&["test", "biuld"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::UnknownSubcommand, Some(1)));
+
+        // Subcommand errors are indexed from the start of the program's args
+        let error = error_for(&["test", "-v", "build", "-x"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::UnknownOption, Some(3)));
+
+        let error = error_for(&["test", "build"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::MissingPositional, None));
+
+        let error = error_for(&["test", "build", "app", "lib"]);
+        assert_eq!((error.kind(), error.index()), (ErrorKind::UnexpectedPositional, Some(3)));
+
+        let env_args: Vec<String> = ["test", "-o", "out", "build", "app"].iter().map(|s| s.to_string()).collect();
+        let matches = matches(&env_args, &vars).unwrap();
+        let error = matches.get_as::<u32>("output").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidValue);
+        assert!(error::Error::source(&error).unwrap().is::<ParseIntError>());
+        assert_eq!(matches.get_as::<u32>("jobs").unwrap_err().kind(), ErrorKind::NoMatch);
+    }
+
+    #[test]
+    fn test_matches_lenient() {
+        let env_args: Vec<String> = ["test", "-v", "--jobs=4", "--target", "x86", "-vXfoo", "-Xv", "-q", "--color",
+                                     "-o", "out", "file", "--", "--verbose"]
+            .iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Verbose output", "o/output#The output file:", ":files...#The files"];
+
+        {
+            let vars = vars("Test", &opts).unwrap();
+            let error = matches(&env_args, &vars).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnknownOption);
+        }
+
+        {
+            let mut vars = vars("Test", &opts).unwrap();
+            vars.lenient = true;
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.remainder(), &["--jobs=4", "--target", "-Xfoo", "-X", "-q", "--color"]);
+            assert_eq!(matches.count("verbose"), 3);
+            assert_eq!(matches.get("output").unwrap(), "out");
+            assert_eq!(matches.get_all("files"), vec!["x86", "file", "--verbose"]);
+        }
+
+        // Unknown opts don't take the wrapper's own args as their values, only the ones left over
+        {
+            let mut vars = vars("Test", &["v/verbose#Verbose output", ":input#The input file", ":output?#The output file"])
+                .unwrap();
+            vars.lenient = true;
+
+            let env_args: Vec<String> = ["test", "--unknown", "in.txt", "-x", "--level=2", "out.txt", "--target", "x86",
+                                         "-vj", "4", "-k"]
+                .iter().map(|s| s.to_string()).collect();
+            let matches = matches(&env_args, &vars).unwrap();
+
+            assert_eq!(matches.remainder(), &["--unknown", "-x", "--level=2", "--target", "x86", "-j", "4", "-k"]);
+            assert_eq!(matches.count("verbose"), 1);
+            assert_eq!(matches.get("input").unwrap(), "in.txt");
+            assert_eq!(matches.get("output").unwrap(), "out.txt");
+        }
+    }
+
+    #[test]
+    fn test_matches_ordering() {
+        let env_args: Vec<String> = ["test", "-v", "exec", "ls", "-la", "--verbose"]
+            .iter().map(|s| s.to_string()).collect();
+        let opts = vec!["v/verbose#Verbose output", "l/long#Long output", "a/all#All files", ":command...#The command"];
+
+        // Opts and args may be intermixed by default
+        {
+            let vars = vars("Test", &opts).unwrap();
+            let matches = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap();
+
+            assert_eq!(matches.count("verbose"), 2);
+            assert!(matches.has_match("long") && matches.has_match("all"));
+            assert_eq!(matches.get_all("command"), vec!["exec", "ls"]);
+        }
+
+        {
+            let mut vars = vars("Test", &opts).unwrap();
+            vars.ordering = Ordering::RequireOrder;
+            let matches = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap();
+
+            assert_eq!(matches.count("verbose"), 1);
+            assert!(!matches.has_match("long") && !matches.has_match("all"));
+            assert_eq!(matches.get_all("command"), vec!["exec", "ls", "-la", "--verbose"]);
+        }
+
+        {
+            let mut env_vars = HashMap::new();
+            env_vars.insert(String::from("POSIXLY_CORRECT"), String::from("1"));
+
+            let mut vars = vars("Test", &opts).unwrap();
+            let matches = matches_with_env(&env_args, &vars, &env_vars).unwrap();
+            assert_eq!(matches.get_all("command"), vec!["exec", "ls", "-la", "--verbose"]);
+
+            // An explicit ordering takes precedence over the environment
+            vars.ordering = Ordering::Permute;
+            let matches = matches_with_env(&env_args, &vars, &env_vars).unwrap();
+            assert_eq!(matches.count("verbose"), 2);
+            assert_eq!(matches.get_all("command"), vec!["exec", "ls"]);
+        }
+
+        // Subcommands follow the same order as their parent
+        {
+            let env_args: Vec<String> = ["test", "exec", "ls", "-la"].iter().map(|s| s.to_string()).collect();
+            let mut vars = vars("Test", &["v/verbose#Verbose output"]).unwrap();
+            vars.ordering = Ordering::RequireOrder;
+            vars.subcommand("exec", "Run a command", &["l/long#Long output", ":command...#The command"]).unwrap();
+
+            let matches = matches_with_env(&env_args, &vars, &HashMap::new()).unwrap();
+            let (_, sub_matches) = matches.subcommand().unwrap();
+            assert!(!sub_matches.has_match("long"));
+            assert_eq!(sub_matches.get_all("command"), vec!["ls", "-la"]);
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_matches_bad() {
         let env_args = vec![String::from("test"), String::from("-a")];
         let opts = vec!["o/opt#An option", "a#An argument:"];
         
-        let mut vars = vars("Test", &opts).unwrap();
-        match matches(&env_args, &mut vars) {
+        let vars = vars("Test", &opts).unwrap();
+        match matches(&env_args, &vars) {
             Ok(m) => m,
             Err(why) => panic!("An error occurred: {}", why)
         };
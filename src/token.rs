@@ -23,28 +23,34 @@ use errors::{Error, ErrorKind};
 pub struct Token {
     pub short_name: String,
     pub long_name: String,
+    pub short_aliases: Vec<String>,
+    pub long_aliases: Vec<String>,
     pub description: String,
     pub is_arg: bool,
     pub has_arg: bool,
     pub is_group: bool,
+    pub is_variadic: bool,
+    pub is_optional: bool,
+    pub is_negatable: bool,
+    pub is_required: bool,
+    pub default: Option<String>,
+    pub env: Option<String>,
+    pub choices: Vec<String>,
     pub padding: usize
 }
 
 pub fn token(input: &str) -> Result<Token, Error> {
-    let mut short_name = String::new();
-    let mut long_name = String::new();
+    let mut short_names: Vec<String> = vec![String::new()];
+    let mut long_names: Vec<String> = Vec::new();
     let mut description = String::new();
+    let mut default: Option<String> = None;
+    let mut env: Option<String> = None;
+    let mut choices: Vec<String> = Vec::new();
     let last_char = input.len() - 1;
 
-    let is_arg = match &input[..1] {
-        ":" => true,
-        _ => false
-    };
+    let is_arg = &input[..1] == ":";
 
-    let has_arg = match &input[last_char..] {
-        ":" => true,
-        _ => false
-    };
+    let has_arg = &input[last_char..] == ":";
 
     if is_arg && has_arg {
         return Err(Error::new(ErrorKind::TokenFormat, String::from(input)));
@@ -60,32 +66,130 @@ pub fn token(input: &str) -> Result<Token, Error> {
 
     let mut current_stage = AnalysisStage::ShortName;
     for c in option.chars() {
-        match c {
-            '/' => current_stage = AnalysisStage::LongName,
-            '#' => current_stage = AnalysisStage::Description,
-            _ => {
-                match current_stage {
-                    AnalysisStage::ShortName => short_name.push(c),
-                    AnalysisStage::LongName => long_name.push(c),
-                    AnalysisStage::Description => description.push(c)
+        match current_stage {
+            // Descriptions are taken verbatim, e.g. "Input and/or output; default=1"
+            AnalysisStage::Description => description.push(c),
+            _ => match c {
+                '/' if current_stage.is_name() => {
+                    long_names.push(String::new());
+                    current_stage = AnalysisStage::LongName;
+                },
+                // Aliases are separated by commas, e.g. q,s/quiet,silent
+                ',' if current_stage == AnalysisStage::ShortName => short_names.push(String::new()),
+                ',' if current_stage == AnalysisStage::LongName => long_names.push(String::new()),
+                '=' if current_stage != AnalysisStage::Default => {
+                    default = Some(String::new());
+                    current_stage = AnalysisStage::Default;
+                },
+                '$' if current_stage != AnalysisStage::Env => {
+                    env = Some(String::new());
+                    current_stage = AnalysisStage::Env;
+                },
+                '@' if current_stage != AnalysisStage::Choices => {
+                    choices.push(String::new());
+                    current_stage = AnalysisStage::Choices;
+                },
+                // Choices are separated by pipes, e.g. @json|yaml|toml
+                '|' if current_stage == AnalysisStage::Choices => choices.push(String::new()),
+                '#' => current_stage = AnalysisStage::Description,
+                _ => {
+                    match current_stage {
+                        AnalysisStage::ShortName => short_names.last_mut().unwrap().push(c),
+                        AnalysisStage::LongName => long_names.last_mut().unwrap().push(c),
+                        AnalysisStage::Default => if let Some(ref mut d) = default { d.push(c) },
+                        AnalysisStage::Env => if let Some(ref mut e) = env { e.push(c) },
+                        AnalysisStage::Choices => choices.last_mut().unwrap().push(c),
+                        AnalysisStage::Description => unreachable!()
+                    }
                 }
             }
         }
     }
 
-    let is_group = if short_name.is_empty() && long_name.is_empty() {
-        true
-    } else {
-        false
-    };
+    // The first of each name is the primary name, with the rest being its aliases
+    let mut short_aliases: Vec<String> = short_names.into_iter().filter(|n| !n.is_empty()).collect();
+    let mut long_aliases: Vec<String> = long_names.into_iter().filter(|n| !n.is_empty()).collect();
+    let mut short_name = if short_aliases.is_empty() { String::new() } else { short_aliases.remove(0) };
+    let mut long_name = if long_aliases.is_empty() { String::new() } else { long_aliases.remove(0) };
+
+    let is_group = short_name.is_empty() && long_name.is_empty();
+
+    // A trailing ellipsis on an arg collects all of the remaining args, e.g. :files...
+    // A trailing question mark, or a default value, marks an arg as optional, e.g. :dest?
+    let mut is_variadic = false;
+    let mut is_optional = false;
+    if is_arg {
+        for name in [&mut short_name, &mut long_name].iter_mut() {
+            is_optional |= strip_suffix(name, "?");
+            is_variadic |= strip_suffix(name, "...");
+        }
+
+        is_optional |= default.is_some();
+    } else if (default.is_some() || env.is_some()) && !has_arg {
+        // Only options which take an argument can have a default value or environment variable
+        return Err(Error::new(ErrorKind::TokenFormat, String::from(input)));
+    }
+
+    if is_arg && (env.is_some() || !short_aliases.is_empty() || !long_aliases.is_empty()) {
+        return Err(Error::new(ErrorKind::TokenFormat, String::from(input)));
+    }
+
+    // Flags can be made negatable, e.g. /[no-]color accepts both --color and --no-color
+    let mut is_negatable = false;
+    for name in Some(&mut long_name).into_iter().chain(long_aliases.iter_mut()) {
+        if name.starts_with("[no-]") {
+            *name = String::from(&name[5..]);
+            is_negatable = true;
+        }
+    }
+
+    if is_negatable && (is_arg || has_arg) {
+        return Err(Error::new(ErrorKind::TokenFormat, String::from(input)));
+    }
+
+    // Only values can be restricted to a set of choices, which must include the default value
+    choices.retain(|c| !c.is_empty());
+    if !choices.is_empty() {
+        let valid_default = match default {
+            Some(ref d) => choices.contains(d),
+            None => true
+        };
+
+        if !(is_arg || has_arg) || !valid_default {
+            return Err(Error::new(ErrorKind::TokenFormat, String::from(input)));
+        }
+    }
+
+    // A trailing exclamation mark on any of an option's names makes it mandatory, e.g. c/config!
+    let mut is_required = false;
+    if !is_arg {
+        let aliases = short_aliases.iter_mut().chain(long_aliases.iter_mut());
+        for name in Some(&mut short_name).into_iter().chain(Some(&mut long_name)).chain(aliases) {
+            is_required |= strip_suffix(name, "!");
+        }
+    }
+
+    // A name made up of only a marker isn't a name, e.g. !#description, rather than a group
+    if !is_group && short_name.is_empty() && long_name.is_empty() {
+        return Err(Error::new(ErrorKind::TokenFormat, String::from(input)));
+    }
 
     Ok(Token {
-        short_name: short_name,
-        long_name: long_name,
-        is_arg: is_arg,
-        has_arg: has_arg,
-        is_group: is_group,
-        description: description,
+        short_name,
+        long_name,
+        short_aliases,
+        long_aliases,
+        is_arg,
+        has_arg,
+        is_group,
+        is_variadic,
+        is_optional,
+        is_negatable,
+        is_required,
+        default,
+        env,
+        choices,
+        description,
         padding: 0
     })
 }
@@ -96,22 +200,55 @@ impl Token {
     }
     
     pub fn len(&self) -> usize {
-        let short_name_empty = self.short_name.is_empty();
-        let long_name_empty = self.long_name.is_empty();
-        
-        let repr = if !short_name_empty && !long_name_empty {
-                format!("-{}, --{}", self.short_name, self.long_name)
-            } else if !short_name_empty && long_name_empty {
-                format!("-{}", self.short_name)
-            } else if short_name_empty && !long_name_empty {
-                format!("--{}", self.long_name)
-            } else {
-                String::new()
-            };
-        
-        repr.len()
+        self.forms().join(", ").len()
     }
-    
+
+    pub fn short_names(&self) -> Vec<&String> {
+        Some(&self.short_name).into_iter().chain(self.short_aliases.iter()).filter(|n| !n.is_empty()).collect()
+    }
+
+    pub fn long_names(&self) -> Vec<&String> {
+        Some(&self.long_name).into_iter().chain(self.long_aliases.iter()).filter(|n| !n.is_empty()).collect()
+    }
+
+    // The long-form name as it's displayed, e.g. [no-]color
+    fn long_form(&self, long_name: &str) -> String {
+        if self.is_negatable {
+            format!("[no-]{}", long_name)
+        } else {
+            String::from(long_name)
+        }
+    }
+
+    // The primary name of the token as it's passed to the program, e.g. --config, or an arg's name
+    pub fn flag_name(&self) -> String {
+        if self.is_arg {
+            self.name()
+        } else if !self.long_name.is_empty() {
+            format!("--{}", self.long_name)
+        } else {
+            format!("-{}", self.short_name)
+        }
+    }
+
+    // Every name of the token as it's displayed, e.g. -c, --[no-]color, --[no-]colour
+    fn forms(&self) -> Vec<String> {
+        let mut forms: Vec<String> = self.short_names().iter().map(|n| format!("-{}", n)).collect();
+        for name in self.long_names() {
+            forms.push(format!("--{}", self.long_form(name)));
+        }
+
+        forms
+    }
+
+    pub fn is_negation(&self, opt_name: &str) -> bool {
+        self.is_negatable && opt_name.starts_with("no-") && self.long_names().iter().any(|n| **n == opt_name[3..])
+    }
+
+    pub fn is_long_name(&self, opt_name: &str) -> bool {
+        self.long_names().iter().any(|n| *n == opt_name) || self.is_negation(opt_name)
+    }
+
     pub fn name(&self) -> String {
         if !self.long_name.is_empty() {
             self.long_name.clone()
@@ -122,33 +259,75 @@ impl Token {
         }
     }
     
+    // An option's usage without any brackets, e.g. -o|--output output
+    pub fn opt_usage(&self) -> String {
+        // Only the primary names are shown, aliases are listed with the options
+        let mut forms: Vec<String> = Vec::new();
+        if !self.short_name.is_empty() {
+            forms.push(format!("-{}", self.short_name));
+        }
+
+        if !self.long_name.is_empty() {
+            forms.push(format!("--{}", self.long_form(&self.long_name)));
+        }
+
+        let mut repr = forms.join("|");
+
+        if self.has_arg && !self.choices.is_empty() {
+            repr.push_str(&format!(" {{{}}}", self.choices.join("|")));
+        } else if self.has_arg {
+            repr.push(' ');
+            repr.push_str(&self.name());
+        }
+
+        repr
+    }
+
+    // Values of options and args with choices must be one of them
+    pub fn check_value(&self, value: &str) -> Result<(), Error> {
+        self.check_value_as(&self.flag_name(), value)
+    }
+
+    // As check_value, but blaming the flag as it was typed, e.g. -f rather than --format
+    pub fn check_value_as(&self, flag: &str, value: &str) -> Result<(), Error> {
+        if self.choices.is_empty() || self.choices.iter().any(|c| c == value) {
+            Ok(())
+        } else {
+            let error = Error::with_related(ErrorKind::InvalidChoice, String::from(flag), self.choices.clone());
+            Err(error.with_value(String::from(value)))
+        }
+    }
+
     pub fn usage(&self) -> Option<String> {
         let mut repr = String::new();
         
         if !self.is_group {
             if !self.is_arg {
-                repr.push('[');
-                
-                if !self.short_name.is_empty() {
-                    repr.push('-');
-                    repr.push_str(&self.short_name);
+                if !self.is_required {
+                    repr.push('[');
                 }
 
-                if !self.long_name.is_empty() {
-                    repr.push_str("|--");
-                    repr.push_str(&self.long_name);
-                }
+                repr.push_str(&self.opt_usage());
 
-                if self.has_arg {
-                    let name = String::from(self.name());
-                    repr.push(' ');
-                    repr.push_str(&name);
+                if !self.is_required {
+                    repr.push(']');
                 }
-                
-                repr.push(']');
             } else {
-                let name = String::from(self.name());
+                let name = self.name();
+
+                if self.is_optional {
+                    repr.push('[');
+                }
+
                 repr.push_str(&name);
+
+                if self.is_variadic {
+                    repr.push_str("...");
+                }
+
+                if self.is_optional {
+                    repr.push(']');
+                }
             }
             
             Some(repr)
@@ -159,35 +338,65 @@ impl Token {
 }
 
 impl Display for Token {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let mut spacing = String::new();
         for _ in 0..self.padding {
             spacing.push(' ');
         }
         
-        let short_name_empty = self.short_name.is_empty();
-        let long_name_empty = self.long_name.is_empty();
-
-        let repr = if self.is_group {
+        let mut repr = if self.is_group {
             format!("\n{}:", self.description)
-        } else if !short_name_empty && !long_name_empty {
-            format!("  -{}, --{}{}  {}", self.short_name, self.long_name, spacing, self.description)
-        } else if short_name_empty && !long_name_empty { 
-            format!("  --{}{}  {}", self.long_name, spacing, self.description)
         } else {
-            format!("  -{}{}  {}", self.short_name, spacing, self.description)
+            format!("  {}{}  {}", self.forms().join(", "), spacing, self.description)
         };
 
+        if self.is_required {
+            repr.push_str(" (required)");
+        }
+
+        if let Some(ref default) = self.default {
+            repr.push_str(&format!(" (default: {})", default));
+        }
+
+        if let Some(ref env) = self.env {
+            repr.push_str(&format!(" (env: {})", env));
+        }
+
+        if !self.choices.is_empty() {
+            repr.push_str(&format!(" (choices: {})", self.choices.join(", ")));
+        }
+
         write!(f, "{}", repr)
     }
 }
 
+// Removes the suffix from the name, returning whether or not it was present
+fn strip_suffix(name: &mut String, suffix: &str) -> bool {
+    if name.ends_with(suffix) {
+        let len = name.len() - suffix.len();
+        name.truncate(len);
+        true
+    } else {
+        false
+    }
+}
+
+#[derive(PartialEq)]
 enum AnalysisStage {
     ShortName,
     LongName,
+    Default,
+    Env,
+    Choices,
     Description
 }
 
+impl AnalysisStage {
+    fn is_name(&self) -> bool {
+        *self == AnalysisStage::ShortName || *self == AnalysisStage::LongName
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Token, token};
@@ -202,10 +411,19 @@ mod tests {
         let control_token = Token {
             short_name: String::from("h"),
             long_name: String::from("help"),
+            short_aliases: Vec::new(),
+            long_aliases: Vec::new(),
             description: String::from("Display the program usage"),
             is_arg: false,
             has_arg: false,
             is_group: false,
+            is_variadic: false,
+            is_optional: false,
+            is_negatable: false,
+            is_required: false,
+            default: None,
+            env: None,
+            choices: Vec::new(),
             padding: 0
         };
 
@@ -222,10 +440,19 @@ mod tests {
         let control_token = Token {
             short_name: String::new(),
             long_name: String::new(),
+            short_aliases: Vec::new(),
+            long_aliases: Vec::new(),
             description: String::from("This is a group"),
             is_arg: false,
             has_arg: false,
             is_group: true,
+            is_variadic: false,
+            is_optional: false,
+            is_negatable: false,
+            is_required: false,
+            default: None,
+            env: None,
+            choices: Vec::new(),
             padding: 0
         };
 
@@ -242,10 +469,19 @@ mod tests {
         let control_token = Token {
             short_name: String::from("o"),
             long_name: String::from("option"),
+            short_aliases: Vec::new(),
+            long_aliases: Vec::new(),
             description: String::from("An option with an argument"),
             is_arg: false,
             has_arg: true,
             is_group: false,
+            is_variadic: false,
+            is_optional: false,
+            is_negatable: false,
+            is_required: false,
+            default: None,
+            env: None,
+            choices: Vec::new(),
             padding: 0
         };
 
@@ -262,14 +498,161 @@ mod tests {
         let control_token = Token {
             short_name: String::from("a"),
             long_name: String::from("arg"),
+            short_aliases: Vec::new(),
+            long_aliases: Vec::new(),
             description: String::from("An argument"),
             is_arg: true,
             has_arg: false,
             is_group: false,
+            is_variadic: false,
+            is_optional: false,
+            is_negatable: false,
+            is_required: false,
+            default: None,
+            env: None,
+            choices: Vec::new(),
+            padding: 0
+        };
+
+        assert_eq!(token, control_token);
+    }
+
+    #[test]
+    fn test_new_token_as_variadic_arg() {
+        let opt = ":files...#Files to process";
+        let token = match token(opt) {
+            Ok(t) => t,
+            Err(why) => panic!("Received error: {}", why)
+        };
+        let control_token = Token {
+            short_name: String::from("files"),
+            long_name: String::new(),
+            short_aliases: Vec::new(),
+            long_aliases: Vec::new(),
+            description: String::from("Files to process"),
+            is_arg: true,
+            has_arg: false,
+            is_group: false,
+            is_variadic: true,
+            is_optional: false,
+            is_negatable: false,
+            is_required: false,
+            default: None,
+            env: None,
+            choices: Vec::new(),
             padding: 0
         };
 
         assert_eq!(token, control_token);
+        assert_eq!(token.usage().unwrap(), "files...");
+    }
+
+    #[test]
+    fn test_new_token_as_optional_arg() {
+        let optional = token(":dest?#The destination").unwrap();
+        let with_default = token(":/dest=out/#The destination; default=out/").unwrap();
+        let variadic = token(":files...?#Files to process").unwrap();
+
+        assert!(optional.is_optional);
+        assert_eq!(optional.name(), "dest");
+        assert_eq!(optional.default, None);
+        assert_eq!(optional.usage().unwrap(), "[dest]");
+
+        assert!(with_default.is_optional);
+        assert_eq!(with_default.name(), "dest");
+        assert_eq!(with_default.default, Some(String::from("out/")));
+        assert_eq!(with_default.description, "The destination; default=out/");
+
+        assert!(variadic.is_optional && variadic.is_variadic);
+        assert_eq!(variadic.usage().unwrap(), "[files...]");
+    }
+
+    #[test]
+    fn test_new_token_with_default() {
+        let token = token("a/addend=1#The right side of the addition equation:").unwrap();
+
+        assert!(token.has_arg);
+        assert!(!token.is_optional);
+        assert_eq!(token.default, Some(String::from("1")));
+        assert_eq!(format!("{}", token), "  -a, --addend  The right side of the addition equation (default: 1)");
+        assert!(super::token("v/verbose=1#A flag with a default").is_err());
+    }
+
+    #[test]
+    fn test_new_token_with_env() {
+        let token = token("o/output=out.txt$APP_OUTPUT#The output file:").unwrap();
+
+        assert_eq!(token.default, Some(String::from("out.txt")));
+        assert_eq!(token.env, Some(String::from("APP_OUTPUT")));
+        assert_eq!(format!("{}", token), "  -o, --output  The output file (default: out.txt) (env: APP_OUTPUT)");
+        assert!(super::token("v/verbose$APP_VERBOSE#A flag with an environment variable").is_err());
+        assert!(super::token(":input$APP_INPUT#An arg with an environment variable").is_err());
+    }
+
+    #[test]
+    fn test_new_token_as_negatable() {
+        let token = token("c/[no-]color#Colorize the output").unwrap();
+
+        assert!(token.is_negatable);
+        assert_eq!(token.name(), "color");
+        assert!(token.is_long_name("color"));
+        assert!(token.is_long_name("no-color"));
+        assert!(!token.is_long_name("no-c"));
+        assert_eq!(token.usage().unwrap(), "[-c|--[no-]color]");
+        assert_eq!(format!("{}", token), "  -c, --[no-]color  Colorize the output");
+        assert!(super::token("/[no-]color#Colorize the output:").is_err());
+    }
+
+    #[test]
+    fn test_new_token_with_aliases() {
+        let token = token("q,s/quiet,silent/mute#Suppress output").unwrap();
+
+        assert_eq!(token.name(), "quiet");
+        assert_eq!(token.short_names(), vec!["q", "s"]);
+        assert_eq!(token.long_names(), vec!["quiet", "silent", "mute"]);
+        assert_eq!(token.usage().unwrap(), "[-q|--quiet]");
+        assert_eq!(format!("{}", token), "  -q, -s, --quiet, --silent, --mute  Suppress output");
+
+        let negatable = super::token("/[no-]color,[no-]colour#Colorize the output").unwrap();
+        assert!(negatable.is_long_name("colour"));
+        assert!(negatable.is_long_name("no-colour"));
+        assert_eq!(format!("{}", negatable), "  --[no-]color, --[no-]colour  Colorize the output");
+        assert!(super::token(":f,file#An arg with an alias").is_err());
+    }
+
+    #[test]
+    fn test_new_token_as_required() {
+        let token = token("c/config!#The configuration file:").unwrap();
+
+        assert!(token.is_required);
+        assert_eq!(token.name(), "config");
+        assert_eq!(token.flag_name(), "--config");
+        assert_eq!(token.usage().unwrap(), "-c|--config config");
+        assert_eq!(format!("{}", token), "  -c, --config  The configuration file (required)");
+        assert!(super::token("!#A required option without a name").is_err());
+
+        let alias = super::token("/config,cfg!#The configuration file:").unwrap();
+        assert!(alias.is_required);
+        assert_eq!(alias.long_names(), vec!["config", "cfg"]);
+        assert!(super::token(":?#An optional arg without a name").is_err());
+        assert!(super::token("#A group").unwrap().is_group);
+    }
+
+    #[test]
+    fn test_new_token_with_choices() {
+        let token = token("f/format=json@json|yaml|toml#The output format:").unwrap();
+
+        assert_eq!(token.choices, vec!["json", "yaml", "toml"]);
+        assert_eq!(token.default, Some(String::from("json")));
+        assert_eq!(token.usage().unwrap(), "[-f|--format {json|yaml|toml}]");
+        assert_eq!(format!("{}", token),
+                   "  -f, --format  The output format (default: json) (choices: json, yaml, toml)");
+        assert!(token.check_value("yaml").is_ok());
+        assert_eq!(token.check_value("xml").unwrap_err().to_string(),
+                   "An invalid value was passed to the program: --format=xml (valid choices: json, yaml, toml)");
+
+        assert!(super::token("f/format=xml@json|yaml#The output format:").is_err());
+        assert!(super::token("v/verbose@yes|no#Verbose output").is_err());
     }
 
     #[test]
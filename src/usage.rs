@@ -20,14 +20,51 @@ use vars::Vars;
 pub fn usage(vars: &Vars) {
     print!("Usage: {} ", vars.program_name);
 
+    // Conflicting options are displayed together, e.g. [--json | --yaml]. An option in several
+    // groups is displayed in each of them, so that every group is shown in full
+    let groups = vars.conflict_groups();
+    let mut displayed: Vec<usize> = Vec::new();
+
     for token in vars.tokens() {
-        if let Some(usage) = token.usage() {
-            print!("{} ", usage);
+        let mut is_grouped = false;
+
+        for (index, group) in groups.iter().enumerate() {
+            if !group.iter().any(|t| t.name() == token.name()) {
+                continue;
+            }
+
+            is_grouped = true;
+            if !displayed.contains(&index) {
+                let usages: Vec<String> = group.iter().map(|t| t.opt_usage()).collect();
+                print!("[{}] ", usages.join(" | "));
+
+                displayed.push(index);
+            }
+        }
+
+        if !is_grouped {
+            if let Some(usage) = token.usage() {
+                print!("{} ", usage);
+            }
         }
     }
+
+    if vars.subcommands().len() > 0 {
+        print!("<command> [<args>]");
+    }
     
     println!("\nOptions:");
     for token in vars.tokens() {
         println!("{}", token);
     }
+
+    if vars.subcommands().len() > 0 {
+        let longest_name_len = vars.subcommands().map(|c| c.name.len()).max().unwrap_or(0);
+
+        println!("\nCommands:");
+        for command in vars.subcommands() {
+            let spacing = " ".repeat(longest_name_len - command.name.len());
+            println!("  {}{}  {}", command.name, spacing, command.description);
+        }
+    }
 }
@@ -15,33 +15,64 @@
  * along with this program.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-use std::collections::{HashMap, VecDeque};
+use std::cmp;
+use std::collections::HashMap;
 use std::slice::Iter;
 
-use errors::Error;
+use errors::{Error, ErrorKind};
 use token::{Token, token};
 
 pub struct Vars {
     tokens: Vec<Token>,
     opts: HashMap<String, usize>,
-    args: VecDeque<usize>,
-    pub program_name: String
+    args: Vec<usize>,
+    commands: Vec<Command>,
+    conflicts: Vec<Vec<usize>>,
+    requirements: Vec<(usize, Vec<usize>)>,
+    pub program_name: String,
+    pub abbreviate: bool,
+    pub lenient: bool,
+    pub ordering: Ordering
+}
+
+// Whether opts may follow args, as with GNU's getopt, or must all come before them, as POSIX requires.
+// By default, opts are permuted unless POSIXLY_CORRECT is set, which an explicit ordering overrides
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ordering {
+    Default,
+    Permute,
+    RequireOrder
+}
+
+pub struct Command {
+    pub name: String,
+    pub description: String,
+    pub vars: Vars
 }
 
 pub fn vars(program_name: &str, options: &[&str]) -> Result<Vars, Error> {
     let mut tokens: Vec<Token> = Vec::new();
     let mut opts: HashMap<String, usize> = HashMap::new();
-    let mut args: VecDeque<usize> = VecDeque::new();
+    let mut args: Vec<usize> = Vec::new();
     let mut index: usize = 0;
     
     // The first option should be the help option, i.e. -h, --help
     let help_token = Token {
         short_name: String::from("h"),
         long_name: String::from("help"),
+        short_aliases: Vec::new(),
+        long_aliases: Vec::new(),
         description: String::from("Display usage information"),
         is_arg: false,
         has_arg: false,
         is_group: false,
+        is_variadic: false,
+        is_optional: false,
+        is_negatable: false,
+        is_required: false,
+        default: None,
+        env: None,
+        choices: Vec::new(),
         padding: 0
     };
     opts.insert(help_token.short_name.clone(), index);
@@ -51,22 +82,33 @@ pub fn vars(program_name: &str, options: &[&str]) -> Result<Vars, Error> {
     index += 1;
 
     // Second, add the other, user defined options
+    let mut variadic = false;
+    let mut optional = false;
     for opt in options.iter() {
-        let token = match token(opt) {
-            Ok(t) => t,
-            Err(why) => return Err(why)
-        };
+        let token = token(opt)?;
 
         if !token.is_group {
             if token.is_arg {
-                args.push_back(index);
+                // A variadic arg consumes everything after it, so it must be the last arg. Args are
+                // filled in order, so a required arg can't follow an optional one either
+                if variadic || (optional && !token.is_optional) {
+                    return Err(Error::new(ErrorKind::TokenFormat, String::from(*opt)));
+                }
+
+                variadic = token.is_variadic;
+                optional = token.is_optional;
+                args.push(index);
             } else {
-                if !token.short_name.is_empty() {
-                    opts.insert(token.short_name.clone(), index);
+                for name in token.short_names() {
+                    opts.insert(name.clone(), index);
                 }
 
-                if !token.long_name.is_empty() {
-                    opts.insert(token.long_name.clone(), index);
+                for name in token.long_names() {
+                    opts.insert(name.clone(), index);
+
+                    if token.is_negatable {
+                        opts.insert(format!("no-{}", name), index);
+                    }
                 }
             }
 
@@ -88,10 +130,16 @@ pub fn vars(program_name: &str, options: &[&str]) -> Result<Vars, Error> {
     }
 
     Ok(Vars {
-        opts: opts,
-        args: args,
-        tokens: tokens,
-        program_name: String::from(program_name)
+        opts,
+        args,
+        tokens,
+        commands: Vec::new(),
+        conflicts: Vec::new(),
+        requirements: Vec::new(),
+        program_name: String::from(program_name),
+        abbreviate: false,
+        lenient: false,
+        ordering: Ordering::Default
     })
 }
 
@@ -104,12 +152,138 @@ impl Vars {
         }
     }
 
+    pub fn set_default(&mut self, opt_name: &str, value: &str) -> Result<(), Error> {
+        let index = match self.opts.get(opt_name) {
+            Some(&index) => index,
+            None => return Err(Error::new(ErrorKind::UnknownOption, String::from(opt_name)))
+        };
+
+        let token = &mut self.tokens[index];
+        if !token.has_arg {
+            return Err(Error::new(ErrorKind::TokenFormat, String::from(opt_name)));
+        }
+
+        if let Err(why) = token.check_value(value) {
+            return Err(Error::new(ErrorKind::TokenFormat, why.to_string()));
+        }
+
+        token.default = Some(String::from(value));
+        Ok(())
+    }
+
+    // Expands an abbreviated long-form opt into the opt it uniquely abbreviates, e.g. --verb into
+    // --verbose. Opts which don't abbreviate anything are returned as-is, as is an empty name,
+    // e.g. --=x, which would otherwise abbreviate every opt
+    pub fn expand_opt(&self, opt_name: &str) -> Result<String, Error> {
+        if opt_name.is_empty() || self.opts.contains_key(opt_name) {
+            return Ok(String::from(opt_name));
+        }
+
+        let mut candidates: Vec<(&String, usize)> = self.opts.iter()
+            .filter(|&(name, &index)| name.starts_with(opt_name) && self.tokens[index].is_long_name(name))
+            .map(|(name, &index)| (name, index))
+            .collect();
+        candidates.sort();
+
+        // Aliases of the same opt don't make an abbreviation ambiguous, e.g. --col for --color and --colour
+        let mut indices: Vec<usize> = candidates.iter().map(|&(_, index)| index).collect();
+        indices.sort();
+        indices.dedup();
+
+        match indices.len() {
+            0 => Ok(String::from(opt_name)),
+            1 => Ok(candidates[0].0.clone()),
+            _ => {
+                let names = candidates.iter().map(|&(name, _)| format!("--{}", name)).collect();
+                Err(Error::with_related(ErrorKind::AmbiguousOption, format!("--{}", opt_name), names))
+            }
+        }
+    }
+
+    // The long-form opts closest to an unknown opt, e.g. --verbose for --verbsoe, for suggesting
+    // to the user
+    pub fn suggest_opt(&self, opt_name: &str) -> Vec<String> {
+        let names = self.opts.iter()
+            .filter(|&(name, &index)| self.tokens[index].is_long_name(name))
+            .map(|(name, _)| name);
+
+        closest(opt_name, names).into_iter().map(|name| format!("--{}", name)).collect()
+    }
+
+    pub fn suggest_subcommand(&self, name: &str) -> Vec<String> {
+        closest(name, self.commands.iter().map(|c| &c.name)).into_iter().cloned().collect()
+    }
+
+    // Declares a set of options of which at most one may be passed, e.g. --json and --yaml
+    pub fn conflicts(&mut self, opt_names: &[&str]) -> Result<(), Error> {
+        if opt_names.len() < 2 {
+            return Err(Error::new(ErrorKind::TokenFormat, opt_names.join(", ")));
+        }
+
+        let mut group: Vec<usize> = Vec::new();
+        for opt_name in opt_names.iter() {
+            match self.opts.get(*opt_name) {
+                // A required option would rule out every other option of the group
+                Some(&index) if self.tokens[index].is_required => {
+                    return Err(Error::new(ErrorKind::TokenFormat, String::from(*opt_name)));
+                },
+                Some(&index) => group.push(index),
+                None => return Err(Error::new(ErrorKind::UnknownOption, String::from(*opt_name)))
+            }
+        }
+
+        self.conflicts.push(group);
+        Ok(())
+    }
+
+    pub fn conflict_groups(&self) -> Vec<Vec<&Token>> {
+        self.conflicts.iter()
+            .map(|group| group.iter().map(|&index| &self.tokens[index]).collect())
+            .collect()
+    }
+
+    // Declares that an option, or arg, may only be passed along with the others, e.g. --key-file
+    // requires --tls
+    pub fn requires(&mut self, name: &str, required_names: &[&str]) -> Result<(), Error> {
+        let index = match self.find_token(name) {
+            Some(index) => index,
+            None => return Err(Error::new(ErrorKind::UnknownOption, String::from(name)))
+        };
+
+        let mut required: Vec<usize> = Vec::new();
+        for required_name in required_names.iter() {
+            match self.find_token(required_name) {
+                Some(i) => required.push(i),
+                None => return Err(Error::new(ErrorKind::UnknownOption, String::from(*required_name)))
+            }
+        }
+
+        self.requirements.push((index, required));
+        Ok(())
+    }
+
+    pub fn requirements(&self) -> Vec<(&Token, Vec<&Token>)> {
+        self.requirements.iter()
+            .map(|&(index, ref required)| {
+                (&self.tokens[index], required.iter().map(|&i| &self.tokens[i]).collect())
+            })
+            .collect()
+    }
+
+    // Finds either an option by any of its names, or an arg by its name
+    fn find_token(&self, name: &str) -> Option<usize> {
+        match self.opts.get(name) {
+            Some(&index) => Some(index),
+            None => self.tokens.iter().position(|t| t.is_arg && t.name() == name)
+        }
+    }
+
     pub fn contains_opt(&self, opt: &str) -> bool {
         self.opts.contains_key(opt)
     }
 
-    pub fn get_arg(&mut self) -> Option<&Token> {
-        if let Some(index) = self.args.pop_front() {
+    pub fn get_arg(&self, position: usize) -> Option<&Token> {
+        if let Some(&index) = self.args.get(position) {
             self.tokens.get(index)
         } else {
             None
@@ -120,7 +294,88 @@ impl Vars {
         self.args.len()
     }
     
-    pub fn tokens(&self) -> Iter<Token>{
+    pub fn tokens(&self) -> Iter<'_, Token> {
         self.tokens.iter()
     }
-}
\ No newline at end of file
+
+    pub fn subcommand(&mut self, name: &str, description: &str, options: &[&str]) -> Result<&mut Vars, Error> {
+        // The first arg picks the subcommand, so a program with subcommands can't take args of its own
+        if name.is_empty() || name.starts_with('-') || self.has_subcommand(name) || !self.args.is_empty() {
+            return Err(Error::new(ErrorKind::TokenFormat, String::from(name)));
+        }
+
+        let program_name = format!("{} {}", self.program_name, name);
+        let sub_vars = vars(&program_name, options)?;
+
+        let command = Command {
+            name: String::from(name),
+            description: String::from(description),
+            vars: sub_vars
+        };
+        self.commands.push(command);
+
+        Ok(&mut self.commands.last_mut().unwrap().vars)
+    }
+
+    pub fn get_subcommand(&self, name: &str) -> Option<&Vars> {
+        match self.commands.iter().find(|c| c.name == name) {
+            Some(command) => Some(&command.vars),
+            None => None
+        }
+    }
+
+    pub fn has_subcommand(&self, name: &str) -> bool {
+        self.commands.iter().any(|c| c.name == name)
+    }
+
+    pub fn subcommands(&self) -> Iter<'_, Command> {
+        self.commands.iter()
+    }
+}
+
+// Finds the names nearest to the given one, ignoring any too different to be a likely typo
+fn closest<'a, I: Iterator<Item = &'a String>>(name: &str, names: I) -> Vec<&'a String> {
+    let max_distance = cmp::max(1, name.chars().count() / 3);
+
+    let mut candidates: Vec<(usize, &String)> = names
+        .map(|n| (edit_distance(name, n), n))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .collect();
+    candidates.sort();
+
+    match candidates.first() {
+        Some(&(nearest, _)) => candidates.iter()
+            .take_while(|&&(distance, _)| distance == nearest)
+            .map(|&(_, n)| n)
+            .collect(),
+        None => Vec::new()
+    }
+}
+
+// The number of single character insertions, deletions, substitutions and adjacent
+// transpositions needed to turn one string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d: Vec<Vec<usize>> = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..a.len() + 1 {
+        for j in 1..b.len() + 1 {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = cmp::min(d[i - 1][j - 1] + cost, cmp::min(d[i - 1][j] + 1, d[i][j - 1] + 1));
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = cmp::min(d[i][j], d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
@@ -6,11 +6,11 @@ use pirate::{Matches, Match, matches, usage, vars};
 fn main() {
     let env_args: Vec<String> = vec![String::from("test"), String::from("-a"), String::from("2"),
                                      String::from("3")];
-    let opts = vec!["a/addend#The right side of the addition equation; default=1:", "#Required Arguments",
+    let opts = vec!["a/addend=1#The right side of the addition equation:", "#Required Arguments",
                     ":augend#The left side of an addition equation"];
-    let mut vars = vars("test", &opts).unwrap();
+    let vars = vars("test", &opts).unwrap();
     
-    let matches: Matches = match matches(&env_args, &mut vars) {
+    let matches: Matches = match matches(&env_args, &vars) {
         Ok(m) => m,
         Err(why) => {
             println!("Error: {}", why);
@@ -24,12 +24,9 @@ fn main() {
         return;
     }
     
-    let augend: i32 = matches.get("augend").unwrap().parse::<i32>().unwrap();
+    let augend: i32 = matches.get_as("augend").unwrap();
 
-    let addend: i32 = match matches.get("addend") {
-        Some(a) => a.parse::<i32>().unwrap(),
-        None => 1
-    };
+    let addend: i32 = matches.get_as("addend").unwrap();
     
     let sum = augend + addend;
     